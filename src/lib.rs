@@ -1,71 +1,293 @@
 //! # Digital Filter
 //!
-//! This crate will provide an interface to a digital FIR filter implementation for no-std
-//! environments that cannot depend on a heap being present. By passing in the filter weights and a
-//! matching empty buffer, this crate will instantiate a digital filter that will accept a stream
-//! of inputs and produce a stream of filtered outputs.
+//! This crate provides digital filter implementations for no-std environments that cannot
+//! depend on a heap being present. By passing in the filter weights, this crate will instantiate
+//! a digital filter (with its own fixed-size, zero-initialised sample buffer) that will accept a
+//! stream of inputs and produce a stream of filtered outputs.
+//!
+//! Alongside the FIR `DigitalFilter`, the crate includes a `Biquad` IIR section that can design
+//! its own digital coefficients from an analog prototype via the bilinear transform, and a set of
+//! multirate building blocks (`Upsampler`, `Downsampler`, `PolyphaseInterpolator`) for integer
+//! sample-rate conversion.
 //!
 //! This crate is effectively an implementation of the `lfilter` function in SciPy. The goal of
-//! this crate is to be a self-contained way to apply a digital filter in an embedded system. It
-//! doesn't perform filter design, so you'll need an external tool to design the filter weights for
-//! input. SciPy and Matlab both have excellent tools for this (`scipy.signal.firwin` for SciPy).
+//! this crate is to be a self-contained way to apply a digital filter in an embedded system. For
+//! FIR filter weight design you'll still need an external tool such as SciPy or Matlab (e.g.
+//! `scipy.signal.firwin`).
 #![no_std]
 #![allow(unused_imports)]
 
-extern crate heapless;
+extern crate libm;
+extern crate num_traits;
 
-use heapless::spsc::Queue;
+use num_traits::Zero;
+use core::ops::{Add, Mul};
 
-type FilterItem = f32;
-type FilterBuf<const N: usize> = [FilterItem; N];
-type FilterRing<const N: usize> = Queue<FilterItem, N>;
+type FilterBuf<T, const TAPS: usize> = [T; TAPS];
 
-pub struct DigitalFilter<const N: usize>
+pub struct DigitalFilter<T, const TAPS: usize>
+where
+    T: Copy + Zero + Add<Output = T> + Mul<Output = T>,
 {
-    coeffs: FilterBuf<N>,
-    buffer: FilterRing<N>,
-    num_taps: usize,
+    coeffs: FilterBuf<T, TAPS>,
+    /// Index-based circular buffer of the last `TAPS` input samples.
+    /// `index` points at the oldest sample, i.e. the next slot to overwrite.
+    buffer: FilterBuf<T, TAPS>,
+    index: usize,
 }
 
-impl<const N: usize> DigitalFilter<N>
+impl<T, const TAPS: usize> DigitalFilter<T, TAPS>
+where
+    T: Copy + Zero + Add<Output = T> + Mul<Output = T>,
 {
     /// Create a new `DigitalFilter` using the provided coefficients.
+    pub fn new(coeffs: FilterBuf<T, TAPS>) -> Self {
+        DigitalFilter { coeffs, buffer: [T::zero(); TAPS], index: 0 }
+    }
+
+
+    pub fn filter(&mut self, input: T) -> T {
+        self.buffer[self.index] = input;
+
+        // `oldest` is the position of the oldest sample; walking forward
+        // from there visits the buffer oldest-to-newest, pairing with
+        // `coeffs` reversed so `coeffs[0]` lands on the newest sample.
+        let oldest = self.index + 1;
+        let output = self.coeffs.iter().rev().enumerate().fold(T::zero(), |acc, (offset, coeff)| {
+            acc + self.buffer[(oldest + offset) % TAPS] * *coeff
+        });
+
+        self.index = (self.index + 1) % TAPS;
+        output
+    }
+
+    /// Filter a whole block of samples at once, writing one output per input
+    /// to `output`.
     ///
-    /// # IMPORTANT: `coeffs` must contain one unused element of 0 at the end
-    ///
-    /// Note that due to current limitations of const generics, we cannot specify that `FilterRing`
-    /// should have size N+1. Therefore, we have to work around this by adding a "dummy" parameter
-    /// to coeffs.
-    pub fn new(coeffs: FilterBuf<N>) -> Self {
-        let num_taps = coeffs.len() - 1;
-        if coeffs[num_taps] != 0. {
-            panic!("Sentinel not found at end of supplied coeffs");
+    /// This reuses the same ring buffer state as `filter`, so consecutive
+    /// calls to `filter_block` (or a mix of `filter_block` and `filter`) see
+    /// no transient reset at the block boundary — it behaves identically to
+    /// calling `filter` once per sample.
+    pub fn filter_block(&mut self, input: &[T], output: &mut [T]) {
+        for (x, y) in input.iter().zip(output.iter_mut()) {
+            *y = self.filter(*x);
         }
-        let mut buffer: FilterRing<N> = Queue::new();
-        for _idx in 0..num_taps {
-            buffer.enqueue(0.).unwrap();
-        }
-        DigitalFilter { coeffs, buffer, num_taps }
     }
 
+    /// Wipe all stored memory from the filter.
+    pub fn clear_buffer(&mut self) {
+        self.buffer = [T::zero(); TAPS];
+        self.index = 0;
+    }
+}
+
+
+/// An iterator adapter that lazily runs each item of the wrapped iterator
+/// through a `DigitalFilter`.
+///
+/// Built with [`FilteredExt::filtered`], this allows filter stages to be
+/// chained with standard iterator combinators instead of hand-rolled loops.
+pub struct Filtered<I, const N: usize> {
+    inner: I,
+    filter: DigitalFilter<f32, N>,
+}
+
+impl<I, const N: usize> Iterator for Filtered<I, N>
+where
+    I: Iterator<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        self.inner.next().map(|input| self.filter.filter(input))
+    }
+}
+
+/// Extension trait adding [`filtered`](FilteredExt::filtered) to any
+/// `Iterator<Item = f32>`.
+pub trait FilteredExt: Iterator<Item = f32> + Sized {
+    /// Wrap this iterator so that every sample is passed through `filter`
+    /// before being yielded.
+    fn filtered<const N: usize>(self, filter: DigitalFilter<f32, N>) -> Filtered<Self, N> {
+        Filtered { inner: self, filter }
+    }
+}
+
+impl<I: Iterator<Item = f32>> FilteredExt for I {}
+
+
+/// A second-order (biquad) IIR filter section, evaluated in Direct Form I.
+///
+/// Where `DigitalFilter` only implements FIR (feed-forward) filters, `Biquad`
+/// supports recursive (feedback) filter sections, which makes it possible to
+/// build compact low-pass, high-pass, and band-pass stages. The filter
+/// evaluates the difference equation
+///
+/// `y[n] = b0*x[n] + b1*x[n-1] + b2*x[n-2] - a1*y[n-1] - a2*y[n-2]`
+///
+/// keeping the last two input samples and the last two output samples as its
+/// entire state (Direct Form I, rather than the single-delay-line Direct
+/// Form II), with no heap allocation required.
+pub struct Biquad {
+    b: [f32; 3],
+    a: [f32; 2],
+    x_hist: [f32; 2],
+    y_hist: [f32; 2],
+}
+
+impl Biquad {
+    /// Design a `Biquad` directly from normalized digital coefficients, where
+    /// `b` is `[b0, b1, b2]` and `a` is `[a1, a2]` (with `a0` implicitly 1).
+    pub fn new(b: [f32; 3], a: [f32; 2]) -> Self {
+        Biquad { b, a, x_hist: [0.; 2], y_hist: [0.; 2] }
+    }
+
+    /// Design a `Biquad`'s digital coefficients from a continuous-time
+    /// (analog) second-order prototype
+    ///
+    /// `H(s) = (b2*s^2 + b1*s + b0) / (a2*s^2 + a1*s + a0)`
+    ///
+    /// using the bilinear transform `s = K*(1 - z^-1)/(1 + z^-1)`.
+    ///
+    /// `analog_b` and `analog_a` are given as `[b0, b1, b2]` and
+    /// `[a0, a1, a2]`. `fs` is the sample rate in Hz. If `prewarp_cutoff` is
+    /// given (in rad/s), `K` is prewarped as
+    /// `K = prewarp_cutoff / tan(prewarp_cutoff / (2*fs))` so that cutoff
+    /// frequency is preserved exactly in the digital filter; otherwise
+    /// `K = 2*fs` is used.
+    pub fn from_analog_prototype(
+        analog_b: [f32; 3],
+        analog_a: [f32; 3],
+        fs: f32,
+        prewarp_cutoff: Option<f32>,
+    ) -> Self {
+        let k = match prewarp_cutoff {
+            Some(wc) => wc / libm::tanf(wc / (2. * fs)),
+            None => 2. * fs,
+        };
+        let k2 = k * k;
+        let [b0, b1, b2] = analog_b;
+        let [a0, a1, a2] = analog_a;
+
+        let b0_d = b2 * k2 + b1 * k + b0;
+        let b1_d = 2. * b0 - 2. * b2 * k2;
+        let b2_d = b2 * k2 - b1 * k + b0;
+
+        let a0_d = a2 * k2 + a1 * k + a0;
+        let a1_d = 2. * a0 - 2. * a2 * k2;
+        let a2_d = a2 * k2 - a1 * k + a0;
+
+        Biquad::new(
+            [b0_d / a0_d, b1_d / a0_d, b2_d / a0_d],
+            [a1_d / a0_d, a2_d / a0_d],
+        )
+    }
 
+    /// Filter a single sample, advancing the filter's internal state.
     pub fn filter(&mut self, input: f32) -> f32 {
-        let _ = self.buffer.dequeue();
-        self.buffer.enqueue(input).unwrap();
-        let mut output: f32 = 0_f32;
-        let mut c_idx = self.num_taps;
-        for el in self.buffer.iter() {
-            c_idx -= 1;
-            output += el * self.coeffs[c_idx];
-        }
+        let output = self.b[0] * input + self.b[1] * self.x_hist[0] + self.b[2] * self.x_hist[1]
+            - self.a[0] * self.y_hist[0]
+            - self.a[1] * self.y_hist[1];
+
+        self.x_hist[1] = self.x_hist[0];
+        self.x_hist[0] = input;
+        self.y_hist[1] = self.y_hist[0];
+        self.y_hist[0] = output;
+
         output
     }
 
     /// Wipe all stored memory from the filter.
     pub fn clear_buffer(&mut self) {
-        while self.buffer.dequeue().is_some() {};
-        for _idx in 0..self.num_taps {
-            self.buffer.enqueue(0.).unwrap();
+        self.x_hist = [0.; 2];
+        self.y_hist = [0.; 2];
+    }
+}
+
+
+/// Upsamples a stream by an integer factor `L`.
+///
+/// Each input sample is zero-stuffed to `L` samples (`L-1` zeros inserted
+/// after it), which are then run through `filter` acting as the
+/// anti-imaging low-pass, producing `L` outputs per input.
+pub struct Upsampler<const N: usize, const L: usize> {
+    filter: DigitalFilter<f32, N>,
+}
+
+impl<const N: usize, const L: usize> Upsampler<N, L> {
+    /// Build an upsampler around an FIR anti-imaging filter.
+    pub fn new(filter: DigitalFilter<f32, N>) -> Self {
+        Upsampler { filter }
+    }
+
+    /// Zero-stuff `input` to `L` samples and run them through the
+    /// anti-imaging filter, writing the `L` outputs to `out`.
+    pub fn process(&mut self, input: f32, out: &mut [f32; L]) {
+        out[0] = self.filter.filter(input);
+        for slot in out.iter_mut().skip(1) {
+            *slot = self.filter.filter(0.);
+        }
+    }
+}
+
+/// Downsamples a stream by an integer factor `M`.
+///
+/// Every input sample is run through `filter` acting as the anti-aliasing
+/// low-pass, but only every `M`th filtered output is emitted.
+pub struct Downsampler<const N: usize> {
+    filter: DigitalFilter<f32, N>,
+    decimation: usize,
+    phase: usize,
+}
+
+impl<const N: usize> Downsampler<N> {
+    /// Build a downsampler around an FIR anti-aliasing filter, emitting
+    /// every `decimation`th output.
+    pub fn new(filter: DigitalFilter<f32, N>, decimation: usize) -> Self {
+        Downsampler { filter, decimation, phase: 0 }
+    }
+
+    /// Filter `input`, returning `Some(output)` only on every `decimation`th
+    /// call.
+    pub fn process(&mut self, input: f32) -> Option<f32> {
+        let output = self.filter.filter(input);
+        let keep = self.phase == 0;
+        self.phase = (self.phase + 1) % self.decimation;
+        if keep { Some(output) } else { None }
+    }
+}
+
+
+/// A polyphase FIR interpolator that decomposes a length-`K*SUBTAPS`
+/// prototype filter into `K` sub-filters ("phases"), avoiding the wasted
+/// multiply-by-zero work a naive zero-stuff-then-filter `Upsampler` performs.
+///
+/// Phase `k` holds prototype taps `k, k+K, k+2*K, ...`; each input sample
+/// runs through every phase against a single shared history, producing `K`
+/// outputs per input with no zero-stuffing required.
+pub struct PolyphaseInterpolator<const K: usize, const SUBTAPS: usize> {
+    phases: [[f32; SUBTAPS]; K],
+    history: [f32; SUBTAPS],
+}
+
+impl<const K: usize, const SUBTAPS: usize> PolyphaseInterpolator<K, SUBTAPS> {
+    /// Build an interpolator from a prototype filter already split into its
+    /// `K` polyphase components, each `SUBTAPS` taps long.
+    pub fn new(phases: [[f32; SUBTAPS]; K]) -> Self {
+        PolyphaseInterpolator { phases, history: [0.; SUBTAPS] }
+    }
+
+    /// Advance the shared history by one input sample and compute the `K`
+    /// interpolated outputs, writing them to `out`.
+    pub fn process(&mut self, input: f32, out: &mut [f32; K]) {
+        for j in (1..SUBTAPS).rev() {
+            self.history[j] = self.history[j - 1];
+        }
+        if SUBTAPS > 0 {
+            self.history[0] = input;
+        }
+        for (phase, out_k) in self.phases.iter().zip(out.iter_mut()) {
+            *out_k = phase.iter().zip(self.history.iter()).map(|(c, h)| c * h).sum();
         }
     }
 }
@@ -74,10 +296,15 @@ impl<const N: usize> DigitalFilter<N>
 #[cfg(test)]
 mod tests {
     use DigitalFilter;
+    use Biquad;
+    use FilteredExt;
+    use Upsampler;
+    use Downsampler;
+    use PolyphaseInterpolator;
 
     #[test]
     fn basic_filter_test() {
-        let coeffs = [1., 1., 1., 0.];
+        let coeffs: [f32; 3] = [1., 1., 1.];
         let mut filter = DigitalFilter::new(coeffs);
         let inputs = [4., 8., 15., 16., 23., 42.];
         let expected_output = [4., 12., 27., 39., 54., 81.];
@@ -90,7 +317,7 @@ mod tests {
 
     #[test]
     fn varying_weight_filter_test() {
-        let coeffs = [1., 2., 3., 0.];
+        let coeffs: [f32; 3] = [1., 2., 3.];
         let mut filter = DigitalFilter::new(coeffs);
         let inputs = [4., 8., 15., 16., 23., 42.];
         let expected_output = [4., 16., 43., 70., 100., 136.];
@@ -101,11 +328,154 @@ mod tests {
         assert_eq!(expected_output, actual_output);
     }
 
+    #[test]
+    fn i32_filter_test() {
+        let coeffs = [1, 1, 1];
+        let mut filter = DigitalFilter::new(coeffs);
+        let inputs = [4, 8, 15, 16, 23, 42];
+        let expected_output = [4, 12, 27, 39, 54, 81];
+        let mut actual_output = [0; 6];
+        for (idx, input) in inputs.iter().enumerate() {
+            actual_output[idx] = filter.filter(*input);
+        }
+        assert_eq!(expected_output, actual_output);
+    }
+
+    #[test]
+    fn f64_filter_test() {
+        let coeffs: [f64; 3] = [1., 2., 3.];
+        let mut filter = DigitalFilter::new(coeffs);
+        let inputs = [4., 8., 15., 16., 23., 42.];
+        let expected_output = [4., 16., 43., 70., 100., 136.];
+        let mut actual_output = [0.; 6];
+        for (idx, input) in inputs.iter().enumerate() {
+            actual_output[idx] = filter.filter(*input);
+        }
+        assert_eq!(expected_output, actual_output);
+    }
+
+    #[test]
+    fn biquad_passthrough_test() {
+        let mut filter = Biquad::new([1., 0., 0.], [0., 0.]);
+        let inputs = [4., 8., 15., 16., 23., 42.];
+        let actual_output: [f32; 6] = [
+            filter.filter(inputs[0]),
+            filter.filter(inputs[1]),
+            filter.filter(inputs[2]),
+            filter.filter(inputs[3]),
+            filter.filter(inputs[4]),
+            filter.filter(inputs[5]),
+        ];
+        assert_eq!(inputs, actual_output);
+    }
+
+    #[test]
+    fn biquad_bilinear_transform_test() {
+        // H(s) = 1 / (s^2 + 1), fs = 1 Hz, no prewarping (K = 2*fs = 2).
+        let mut filter = Biquad::from_analog_prototype([1., 0., 0.], [1., 0., 1.], 1., None);
+        let output = filter.filter(1.);
+        assert!((output - 0.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn biquad_impulse_response_exercises_feedback_test() {
+        // H(s) = 1 / (s^2 + s + 1), fs = 1 Hz, no prewarping (K = 2*fs = 2).
+        // Reference values computed independently from the difference
+        // equation; with nonzero a1/a2 this only matches if the y_hist
+        // feedback terms are wired up (and signed) correctly.
+        let mut filter = Biquad::from_analog_prototype([1., 0., 0.], [1., 1., 1.], 1., None);
+        let impulse = [1., 0., 0., 0., 0.];
+        let expected_output = [0.142857, 0.408163, 0.431487, 0.194919, -0.017850];
+        let mut actual_output = [0.; 5];
+        for (idx, input) in impulse.iter().enumerate() {
+            actual_output[idx] = filter.filter(*input);
+        }
+        for (actual, expected) in actual_output.iter().zip(expected_output.iter()) {
+            assert!((actual - expected).abs() < 1e-4, "{} != {}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn filtered_iterator_test() {
+        let coeffs: [f32; 3] = [1., 1., 1.];
+        let filter = DigitalFilter::new(coeffs);
+        let inputs = [4., 8., 15., 16., 23., 42.];
+        let expected_output = [4., 12., 27., 39., 54., 81.];
+        let actual_output: [f32; 6] = {
+            let mut iter = inputs.iter().cloned().filtered(filter);
+            let mut out = [0.; 6];
+            for slot in out.iter_mut() {
+                *slot = iter.next().unwrap();
+            }
+            out
+        };
+        assert_eq!(expected_output, actual_output);
+    }
+
+    #[test]
+    fn upsampler_averaging_kernel_test() {
+        let coeffs: [f32; 2] = [0.5, 0.5];
+        let filter = DigitalFilter::new(coeffs);
+        let mut upsampler: Upsampler<2, 2> = Upsampler::new(filter);
+        let inputs = [0., 1., 2., 3.];
+        let expected_output = [[0., 0.], [0.5, 0.5], [1.0, 1.0], [1.5, 1.5]];
+        let mut actual_output = [[0.; 2]; 4];
+        for (idx, input) in inputs.iter().enumerate() {
+            upsampler.process(*input, &mut actual_output[idx]);
+        }
+        assert_eq!(expected_output, actual_output);
+    }
+
+    #[test]
+    fn downsampler_test() {
+        let coeffs: [f32; 2] = [1., 0.];
+        let filter = DigitalFilter::new(coeffs);
+        let mut downsampler: Downsampler<2> = Downsampler::new(filter, 2);
+        let inputs = [4., 8., 15., 16., 23., 42.];
+        let outputs: [Option<f32>; 6] = [
+            downsampler.process(inputs[0]),
+            downsampler.process(inputs[1]),
+            downsampler.process(inputs[2]),
+            downsampler.process(inputs[3]),
+            downsampler.process(inputs[4]),
+            downsampler.process(inputs[5]),
+        ];
+        assert_eq!(outputs, [Some(4.), None, Some(15.), None, Some(23.), None]);
+    }
 
     #[test]
-    #[should_panic]
-    fn enforce_sentinel_suffix() {
-        let coeffs = [1., 1., 1., 1.]; // No sentinel 0 at the end
-        DigitalFilter::new(coeffs);
+    fn polyphase_matches_zero_stuffed_direct_fir_test() {
+        // Prototype h = [1, 2, 3, 4], split into K=2 phases of SUBTAPS=2:
+        // phase[k][j] = h[k + j*K].
+        let direct_coeffs: [f32; 4] = [1., 2., 3., 4.];
+        let mut direct = Upsampler::<4, 2>::new(DigitalFilter::new(direct_coeffs));
+        let mut polyphase = PolyphaseInterpolator::<2, 2>::new([[1., 3.], [2., 4.]]);
+
+        let inputs = [10., 20., 30.];
+        for input in inputs.iter() {
+            let mut direct_out = [0.; 2];
+            direct.process(*input, &mut direct_out);
+            let mut polyphase_out = [0.; 2];
+            polyphase.process(*input, &mut polyphase_out);
+            assert_eq!(direct_out, polyphase_out);
+        }
+    }
+
+    #[test]
+    fn filter_block_matches_per_sample_filter_test() {
+        let inputs: [f32; 6] = [4., 8., 15., 16., 23., 42.];
+
+        let coeffs: [f32; 3] = [1., 2., 3.];
+        let mut whole_block_filter = DigitalFilter::new(coeffs);
+        let mut whole_block_output = [0.; 6];
+        whole_block_filter.filter_block(&inputs, &mut whole_block_output);
+
+        let coeffs: [f32; 3] = [1., 2., 3.];
+        let mut split_block_filter = DigitalFilter::new(coeffs);
+        let mut split_block_output = [0.; 6];
+        split_block_filter.filter_block(&inputs[..3], &mut split_block_output[..3]);
+        split_block_filter.filter_block(&inputs[3..], &mut split_block_output[3..]);
+
+        assert_eq!(whole_block_output, split_block_output);
     }
 }